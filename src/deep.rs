@@ -0,0 +1,338 @@
+//! Per-unit deep analysis: parses the per-setting breakdown from
+//! `systemd-analyze security <unit> --json=short`, runs it through a set of
+//! [`Rule`]s to produce [`Diagnostic`]s, and can emit suggested fixes as
+//! systemd drop-in override files.
+
+use colored::*;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// A single hardening-relevant setting reported for one unit, e.g.
+/// `NoNewPrivileges=no` contributing `0.2` to the unit's overall exposure.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub unit: String,
+    pub setting: String,
+    pub value: String,
+    pub exposure_contribution: f64,
+    pub description: String,
+}
+
+/// Severity of a [`Diagnostic`], reusing the same tiers as the aggregate
+/// `predicate` field so both can be rendered with [`crate::colorize_predicate`].
+/// Serializes to the same `"OK"`/`"MEDIUM"`/`"EXPOSED"`/`"UNSAFE"` strings as
+/// `Service::predicate`, for consistency under `--json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Severity {
+    Ok,
+    Medium,
+    Exposed,
+    Unsafe,
+}
+
+impl Severity {
+    /// Bucket a single setting's exposure contribution into a severity
+    /// tier. Unlike the aggregate 0-10 `predicate` scale (a sum across
+    /// dozens of settings), a single directive rarely contributes more than
+    /// ~1.5 toward that total even when fully unset, so the cutoffs here
+    /// are scaled down from the aggregate ones rather than reused verbatim.
+    fn from_contribution(contribution: f64) -> Self {
+        if contribution >= 1.5 {
+            Severity::Unsafe
+        } else if contribution >= 0.7 {
+            Severity::Exposed
+        } else if contribution > 0.0 {
+            Severity::Medium
+        } else {
+            Severity::Ok
+        }
+    }
+
+    /// The predicate string this severity corresponds to, for reuse with
+    /// [`crate::colorize_predicate`].
+    pub fn as_predicate(&self) -> &'static str {
+        match self {
+            Severity::Ok => "OK",
+            Severity::Medium => "MEDIUM",
+            Severity::Exposed => "EXPOSED",
+            Severity::Unsafe => "UNSAFE",
+        }
+    }
+}
+
+/// A diagnostic raised by a [`Rule`] against a single [`Finding`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub unit: String,
+    pub setting: String,
+    pub severity: Severity,
+    pub message: String,
+    pub suggested_fix: Option<String>,
+}
+
+/// Something that inspects a single [`Finding`] and optionally raises a
+/// [`Diagnostic`] for it. Implement this to add new hardening checks.
+pub trait Rule {
+    fn check(&self, finding: &Finding) -> Option<Diagnostic>;
+}
+
+/// Flags any unset (non-hardened) directive whose exposure contribution is
+/// non-negligible, suggesting the directive be turned on.
+struct UnsetHighContributionRule;
+
+impl Rule for UnsetHighContributionRule {
+    fn check(&self, finding: &Finding) -> Option<Diagnostic> {
+        let is_unset = matches!(finding.value.as_str(), "no" | "unset" | "");
+        if !is_unset || finding.exposure_contribution <= 0.0 {
+            return None;
+        }
+
+        Some(Diagnostic {
+            unit: finding.unit.clone(),
+            setting: finding.setting.clone(),
+            severity: Severity::from_contribution(finding.exposure_contribution),
+            message: format!(
+                "{} is not set ({}), contributing {:.2} to exposure",
+                finding.setting, finding.description, finding.exposure_contribution
+            ),
+            suggested_fix: Some(format!("{}=yes", finding.setting)),
+        })
+    }
+}
+
+/// The built-in rule set applied by `--deep`.
+pub fn default_rules() -> Vec<Box<dyn Rule>> {
+    vec![Box::new(UnsetHighContributionRule)]
+}
+
+/// Run `systemd-analyze security <unit> --json=short` and parse the
+/// per-setting breakdown into [`Finding`]s. This is the single-unit
+/// invocation dispatched to worker threads by [`analyze_units_parallel`].
+pub fn analyze_unit(unit: &str, debug: bool) -> Result<Vec<Finding>, String> {
+    let output = Command::new("systemd-analyze")
+        .args(["security", unit, "--json=short", "--no-pager"])
+        .output()
+        .map_err(|err| format!("failed to execute systemd-analyze for '{unit}': {err}"))?;
+
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("systemd-analyze failed for '{unit}': {err}"));
+    }
+
+    if debug {
+        println!("{} {}", "Raw JSON output for".bold().yellow(), unit.bold());
+        println!("{}", String::from_utf8_lossy(&output.stdout).green());
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|err| format!("failed to parse systemd-analyze json for '{unit}': {err}"))?;
+
+    let mut findings = Vec::new();
+    if let Some(entries) = json.as_array() {
+        for entry in entries {
+            let setting = entry.get("name").and_then(|v| v.as_str());
+            let description = entry
+                .get("description")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let exposure_contribution = entry
+                .get("exposure")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0);
+            let value = match entry.get("set") {
+                Some(Value::Bool(true)) => "yes".to_string(),
+                Some(Value::Bool(false)) => "no".to_string(),
+                Some(other) => other.to_string(),
+                None => "unset".to_string(),
+            };
+
+            if let Some(setting) = setting {
+                findings.push(Finding {
+                    unit: unit.to_string(),
+                    setting: setting.to_string(),
+                    value,
+                    exposure_contribution,
+                    description: description.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+/// A single unit's deep-scan outcome: its name alongside either the parsed
+/// findings or the error that made it fail independently of its peers.
+type UnitResult = (String, Result<Vec<Finding>, String>);
+
+/// Run [`analyze_unit`] for every unit in `units` across a bounded pool of
+/// `jobs` worker threads, preserving `units`' original ordering in the
+/// returned results regardless of which worker finishes first. A failure
+/// for one unit is returned as an `Err` alongside that unit's name rather
+/// than aborting the other workers.
+pub fn analyze_units_parallel(units: &[String], debug: bool, jobs: usize) -> Vec<UnitResult> {
+    let jobs = jobs.max(1);
+
+    let mut chunks: Vec<Vec<(usize, String)>> = vec![Vec::new(); jobs];
+    for (index, unit) in units.iter().enumerate() {
+        chunks[index % jobs].push((index, unit.clone()));
+    }
+
+    let chunk_results: Vec<Vec<(usize, UnitResult)>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(|(index, unit)| {
+                            let result = analyze_unit(&unit, debug);
+                            (index, (unit, result))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+
+    // Pair each result back up with its unit by the index it was dispatched
+    // under, not by unit name: `units` isn't guaranteed to be deduplicated,
+    // and a name-keyed map would let a second occurrence of the same unit
+    // steal (or miss) the first occurrence's result.
+    // A worker that panicked returns no results; its units get an error in
+    // their slot instead of being dropped from the output.
+    let mut slots: Vec<Option<UnitResult>> = (0..units.len()).map(|_| None).collect();
+    for (index, result) in chunk_results.into_iter().flatten() {
+        slots[index] = Some(result);
+    }
+    slots
+        .into_iter()
+        .zip(units)
+        .map(|(slot, unit)| {
+            slot.unwrap_or_else(|| {
+                let message = format!("deep scan of '{unit}' produced no result");
+                (unit.clone(), Err(message))
+            })
+        })
+        .collect()
+}
+
+/// Run every [`Rule`] against every [`Finding`] and collect the resulting
+/// diagnostics.
+pub fn diagnose(findings: &[Finding], rules: &[Box<dyn Rule>]) -> Vec<Diagnostic> {
+    findings
+        .iter()
+        .flat_map(|finding| rules.iter().filter_map(|rule| rule.check(finding)))
+        .collect()
+}
+
+/// Write a systemd drop-in override (`<unit>.d/50-ssa-hardening.conf`) under
+/// `dir`, containing every diagnostic's suggested fix as a `[Service]`
+/// directive assignment.
+pub fn write_fix_dropin(unit: &str, diagnostics: &[Diagnostic], dir: &Path) -> io::Result<()> {
+    let fixes: Vec<&str> = diagnostics
+        .iter()
+        .filter_map(|d| d.suggested_fix.as_deref())
+        .collect();
+
+    if fixes.is_empty() {
+        return Ok(());
+    }
+
+    let dropin_dir = dir.join(format!("{unit}.d"));
+    fs::create_dir_all(&dropin_dir)?;
+
+    let mut contents = String::from("[Service]\n");
+    for fix in fixes {
+        contents.push_str(fix);
+        contents.push('\n');
+    }
+
+    fs::write(dropin_dir.join("50-ssa-hardening.conf"), contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_units_parallel_isolates_per_unit_failures_and_preserves_order() {
+        let units: Vec<String> = vec![
+            "definitely-not-a-real-unit-a.service".to_string(),
+            "definitely-not-a-real-unit-b.service".to_string(),
+            "definitely-not-a-real-unit-c.service".to_string(),
+        ];
+
+        let results = analyze_units_parallel(&units, false, 2);
+
+        // One worker's failure (a unit systemd-analyze can't find, or the
+        // binary being absent entirely) must not abort the others: every
+        // unit gets its own independent result, in the original order.
+        assert_eq!(results.len(), units.len());
+        for (expected_unit, (unit, _result)) in units.iter().zip(results.iter()) {
+            assert_eq!(unit, expected_unit);
+        }
+    }
+
+    #[test]
+    fn analyze_units_parallel_pairs_duplicate_unit_names_positionally() {
+        let units: Vec<String> = vec![
+            "definitely-not-a-real-unit-dup.service".to_string(),
+            "definitely-not-a-real-unit-dup.service".to_string(),
+            "definitely-not-a-real-unit-dup.service".to_string(),
+        ];
+
+        let results = analyze_units_parallel(&units, false, 2);
+
+        // A name-keyed map would let the first occurrence's result be
+        // `remove`d and handed to every later occurrence of the same name,
+        // leaving those later slots to fall into the "produced no result"
+        // fallback. Pairing by dispatch index instead means every slot gets
+        // its own worker's outcome, never that fallback.
+        assert_eq!(results.len(), units.len());
+        for (unit, result) in &results {
+            assert_eq!(unit, "definitely-not-a-real-unit-dup.service");
+            if let Err(message) = result {
+                assert!(!message.contains("produced no result"));
+            }
+        }
+    }
+
+    #[test]
+    fn diagnose_collects_diagnostics_from_every_matching_rule() {
+        struct AlwaysFlag;
+        impl Rule for AlwaysFlag {
+            fn check(&self, finding: &Finding) -> Option<Diagnostic> {
+                Some(Diagnostic {
+                    unit: finding.unit.clone(),
+                    setting: finding.setting.clone(),
+                    severity: Severity::Medium,
+                    message: "flagged".to_string(),
+                    suggested_fix: None,
+                })
+            }
+        }
+
+        let findings = vec![Finding {
+            unit: "a.service".to_string(),
+            setting: "NoNewPrivileges".to_string(),
+            value: "no".to_string(),
+            exposure_contribution: 0.5,
+            description: "".to_string(),
+        }];
+        let rules: Vec<Box<dyn Rule>> = vec![Box::new(AlwaysFlag), Box::new(AlwaysFlag)];
+
+        let diagnostics = diagnose(&findings, &rules);
+        assert_eq!(diagnostics.len(), 2);
+    }
+}
@@ -1,9 +1,13 @@
 use clap::Parser;
 use colored::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::process::Command;
 
+mod deep;
+mod policy;
+mod snapshot;
+
 #[derive(Parser)]
 struct Args {
     /// number of top services to display
@@ -37,10 +41,110 @@ struct Args {
     /// output results in json format
     #[arg(long)]
     json: bool,
+
+    /// evaluate a boolean policy expression against every unit and exit
+    /// non-zero if any unit violates it, e.g. `predicate != "UNSAFE" && exposure < 7.0`
+    #[arg(long)]
+    policy: Option<String>,
+
+    /// read the policy expression from a file instead of `--policy`
+    #[arg(long)]
+    policy_file: Option<String>,
+
+    /// number of policy violations tolerated before exiting non-zero
+    #[arg(long, default_value_t = 0)]
+    policy_max_failures: usize,
+
+    /// run a per-unit deep scan (systemd-analyze security <unit>) and
+    /// surface individual hardening directives as diagnostics
+    #[arg(long)]
+    deep: bool,
+
+    /// with --deep, write a suggested drop-in override per unit into this
+    /// directory (<unit>.d/50-ssa-hardening.conf)
+    #[arg(long)]
+    fix_output: Option<String>,
+
+    /// number of worker threads for --deep scanning (defaults to the
+    /// number of logical CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// serialize this run's results to a snapshot file for later comparison
+    #[arg(long)]
+    snapshot: Option<String>,
+
+    /// compare this run against a prior --snapshot file and report regressions
+    #[arg(long)]
+    compare: Option<String>,
+
+    /// only keep units whose name contains (or, with --exact, equals) this pattern
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// with --filter, match the unit name exactly instead of by substring
+    #[arg(long)]
+    exact: bool,
+
+    /// print only matching unit names, skipping the full analysis formatting
+    #[arg(long)]
+    list: bool,
+
+    /// sort services by this key instead of the order systemd-analyze reported them in
+    #[arg(long, value_enum)]
+    sort_by: Option<SortKey>,
+
+    /// reverse the sort order
+    #[arg(long)]
+    reverse: bool,
+}
+
+/// Sort keys accepted by `--sort-by`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum SortKey {
+    Exposure,
+    Happy,
+    Unit,
+}
+
+/// Sort `services` in place by `key`. Ascending by default; `reverse`
+/// inverts that. Applied uniformly whether or not `--top-n` is set.
+fn sort_services(services: &mut [Service], key: SortKey, reverse: bool) {
+    services.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Exposure => a
+                .exposure
+                .partial_cmp(&b.exposure)
+                .unwrap_or(std::cmp::Ordering::Equal),
+            SortKey::Happy => {
+                let a_score = happiness_score(&a.happy).unwrap_or(0.0);
+                let b_score = happiness_score(&b.happy).unwrap_or(0.0);
+                a_score
+                    .partial_cmp(&b_score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            }
+            SortKey::Unit => a.unit.cmp(&b.unit),
+        };
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// Whether `unit` matches `--filter <pattern>`: a whole-name match with
+/// `--exact`, a substring match otherwise.
+fn unit_matches(unit: &str, pattern: &str, exact: bool) -> bool {
+    if exact {
+        unit == pattern
+    } else {
+        unit.contains(pattern)
+    }
 }
 
 // store unit details in a struct
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Service {
     /// name of the unit
     unit: String,
@@ -56,7 +160,7 @@ struct Service {
     happy: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct AnalysisResult {
     average_exposure: f64,
     average_happiness: f64,
@@ -65,7 +169,7 @@ struct AnalysisResult {
 
 fn run_systemd_analyze(debug: bool) -> Vec<Service> {
     let output = Command::new("systemd-analyze")
-        .args(&["security", "--json=short", "--no-pager"])
+        .args(["security", "--json=short", "--no-pager"])
         .output()
         .expect("failed to execute process");
 
@@ -118,23 +222,29 @@ fn calculate_exposure_average(services: &[Service]) -> f64 {
     total_exposure / services.len() as f64
 }
 
+/// The happiness scale `systemd-analyze` encodes as emoji, from happiest to
+/// saddest. Shared by `calculate_happiness_average` and `--sort-by happy`
+/// so both rank emoji the same way instead of by raw codepoint.
+const HAPPINESS_SCORES: [(&str, f64); 5] = [
+    ("😀", 5.0),
+    ("🙂", 4.0),
+    ("😐", 3.0),
+    ("🙁", 2.0),
+    ("😨", 1.0),
+];
+
+fn happiness_score(happy: &str) -> Option<f64> {
+    HAPPINESS_SCORES
+        .iter()
+        .find_map(|(h, s)| if happy == *h { Some(*s) } else { None })
+}
+
 fn calculate_happiness_average(services: &[Service]) -> f64 {
-    let happiness_map = vec![
-        ("😀", 5.0),
-        ("🙂", 4.0),
-        ("😐", 3.0),
-        ("🙁", 2.0),
-        ("😨", 1.0),
-    ];
     let mut total_happiness = 0.0;
     let mut count = 0;
 
     for service in services {
-        if let Some(&score) =
-            happiness_map
-                .iter()
-                .find_map(|(h, s)| if service.happy == *h { Some(s) } else { None })
-        {
+        if let Some(score) = happiness_score(&service.happy) {
             total_happiness += score;
             count += 1;
         } else {
@@ -149,6 +259,234 @@ fn calculate_happiness_average(services: &[Service]) -> f64 {
     }
 }
 
+/// The outcome of `enforce_policy`, threaded back to `main` so the JSON
+/// payload can be folded into the single top-level document `main` prints
+/// and the exit decision can be deferred until after that document is out.
+struct PolicyOutcome<'a> {
+    failures: Vec<&'a Service>,
+    exceeded: bool,
+}
+
+/// Read the `--policy`/`--policy-file` expression, if any was given, parse
+/// it, and evaluate it against every service. Returns `None` if no policy
+/// was supplied. A parse error is reported and exits the process
+/// immediately (before the combined JSON document is printed), since a broken policy
+/// should fail a CI pipeline rather than be silently ignored. Whether too
+/// many units violated the policy is reported back via `PolicyOutcome`
+/// rather than exited on the spot, so the caller can print the combined
+/// JSON document first.
+fn enforce_policy<'a>(services: &'a [Service], args: &Args) -> Option<PolicyOutcome<'a>> {
+    let raw = match (&args.policy, &args.policy_file) {
+        (Some(expr), _) => expr.clone(),
+        (None, Some(path)) => match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!(
+                    "{} could not read policy file '{}': {}",
+                    "error:".red().bold(),
+                    path,
+                    err
+                );
+                std::process::exit(1);
+            }
+        },
+        (None, None) => return None,
+    };
+
+    let expr = match policy::parse(raw.trim()) {
+        Ok(expr) => expr,
+        Err(err) => {
+            eprintln!("{} {}", "error:".red().bold(), err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut failures = Vec::new();
+    for service in services {
+        match policy::evaluate(&expr, service) {
+            Ok(true) => {}
+            Ok(false) => failures.push(service),
+            Err(err) => {
+                eprintln!("{} {}", "error:".red().bold(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        return Some(PolicyOutcome {
+            failures,
+            exceeded: false,
+        });
+    }
+
+    // Under --json, the violations are folded by `main` into the single
+    // JSON document it prints, instead of being printed here.
+    if !args.json {
+        println!("\n{}", "## Policy violations".bold().red());
+        for service in &failures {
+            println!(
+                "{} {} {} ({} {:.2})",
+                "•".red(),
+                service.unit.bold(),
+                "->".blue(),
+                colorize_predicate(&service.predicate),
+                service.exposure
+            );
+        }
+    }
+
+    let exceeded = failures.len() > args.policy_max_failures;
+    Some(PolicyOutcome { failures, exceeded })
+}
+
+/// The diagnostics and warnings gathered by `run_deep_mode`, threaded back
+/// to `main` so they can be folded into the single top-level JSON document
+/// `main` prints instead of `run_deep_mode` printing its own.
+struct DeepOutput {
+    diagnostics: Vec<deep::Diagnostic>,
+    warnings: Vec<String>,
+}
+
+/// With `--deep`, run a per-unit deep scan for every service in `services`,
+/// print the resulting diagnostics, and (with `--fix-output`) write a
+/// suggested drop-in override per unit. Returns `None` if `--deep` wasn't
+/// passed.
+///
+/// The human-readable output is printed here as it's found; under `--json`
+/// it's suppressed and the diagnostics/warnings are returned instead, for
+/// `main` to fold into the single JSON document it prints.
+fn run_deep_mode(services: &[Service], args: &Args) -> Option<DeepOutput> {
+    if !args.deep {
+        return None;
+    }
+
+    let rules = deep::default_rules();
+    let jobs = args.jobs.unwrap_or_else(num_cpus::get);
+    let units: Vec<String> = services.iter().map(|s| s.unit.clone()).collect();
+
+    if !args.json {
+        println!("\n{}", "## Deep analysis".bold().cyan());
+    }
+
+    let mut all_diagnostics: Vec<deep::Diagnostic> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
+
+    for (unit, result) in deep::analyze_units_parallel(&units, args.debug, jobs) {
+        let findings = match result {
+            Ok(findings) => findings,
+            Err(err) => {
+                if args.json {
+                    warnings.push(err);
+                } else {
+                    println!("{} {}", "warning:".yellow().bold(), err);
+                }
+                continue;
+            }
+        };
+
+        let diagnostics = deep::diagnose(&findings, &rules);
+        if diagnostics.is_empty() {
+            continue;
+        }
+
+        if !args.json {
+            println!("\n{} {}", "•".blue(), unit.bold());
+            for diag in &diagnostics {
+                println!(
+                    "  {} {}: {}",
+                    colorize_predicate(diag.severity.as_predicate()),
+                    diag.setting.bold(),
+                    diag.message
+                );
+            }
+        }
+
+        if let Some(dir) = &args.fix_output {
+            // diagnostics is non-empty here, so every entry's `unit` matches
+            // this iteration's unit; reading it from the diagnostic itself
+            // (rather than the outer loop binding) is what makes that field
+            // load-bearing instead of dead weight.
+            if let Err(err) = deep::write_fix_dropin(
+                &diagnostics[0].unit,
+                &diagnostics,
+                std::path::Path::new(dir),
+            ) {
+                let message = format!("failed to write drop-in for '{}': {}", unit, err);
+                if args.json {
+                    warnings.push(message);
+                } else {
+                    println!("{} {}", "warning:".yellow().bold(), message);
+                }
+            }
+        }
+
+        all_diagnostics.extend(diagnostics);
+    }
+
+    Some(DeepOutput {
+        diagnostics: all_diagnostics,
+        warnings,
+    })
+}
+
+/// Load the baseline snapshot at `path` and diff it against `result`.
+///
+/// Under `--json` the diff is handed back to `main` to fold into the single
+/// JSON document it prints, instead of being printed here; in human mode
+/// it's rendered directly. Either way, the caller decides what to do about
+/// a regression (and when to exit) once every other side effect for this
+/// run has happened.
+fn run_compare_mode(path: &str, result: &AnalysisResult, json: bool) -> snapshot::SnapshotDiff {
+    let baseline = match snapshot::load(path) {
+        Ok(baseline) => baseline,
+        Err(err) => {
+            eprintln!("{} {}", "error:".red().bold(), err);
+            std::process::exit(1);
+        }
+    };
+
+    let diff = snapshot::diff(&baseline, result);
+
+    if !json {
+        println!("\n{}", "## Snapshot comparison".bold().cyan());
+        println!(
+            "Average exposure delta: {:+.2} | Average happiness delta: {:+.2}",
+            diff.average_exposure_delta, diff.average_happiness_delta
+        );
+
+        for unit in &diff.units {
+            let label = match unit.status {
+                snapshot::UnitStatus::New => "new".blue(),
+                snapshot::UnitStatus::Removed => "removed".normal(),
+                snapshot::UnitStatus::Changed if unit.regressed => "worse".red().bold(),
+                snapshot::UnitStatus::Changed => "better".green(),
+                snapshot::UnitStatus::Unchanged => continue,
+            };
+
+            let before = unit.predicate_before.as_deref().unwrap_or("-");
+            let after = unit.predicate_after.as_deref().unwrap_or("-");
+            println!(
+                "{} {} [{}] {} -> {}",
+                "•".blue(),
+                unit.unit.bold(),
+                label,
+                colorize_predicate(before),
+                colorize_predicate(after)
+            );
+        }
+    }
+
+    if diff.any_regression {
+        eprintln!(
+            "{} one or more units regressed since the snapshot",
+            "error:".red().bold()
+        );
+    }
+
+    diff
+}
+
 fn colorize_predicate(predicate: &str) -> ColoredString {
     match predicate {
         "OK" => predicate.green(),
@@ -175,7 +513,7 @@ fn main() {
         _ => args.predicate.as_deref(),
     };
 
-    let mut filtered_services = if let Some(pred) = predicate {
+    let mut filtered_services: Vec<Service> = if let Some(pred) = predicate {
         services
             .iter()
             .filter(|s| s.predicate == pred)
@@ -185,37 +523,54 @@ fn main() {
         services.clone()
     };
 
-    // Apply --top-n after filtering by predicate
-    // Since we're using a Vec, we can just sort
-    // and take the top n elements. This is better
-    // than my previous approach.
+    if let Some(pattern) = &args.filter {
+        filtered_services.retain(|s| unit_matches(&s.unit, pattern, args.exact));
+    }
+
+    // Sort uniformly whether or not --top-n is set: an explicit --sort-by
+    // always wins, otherwise --top-n falls back to the legacy
+    // descending-by-exposure behavior.
+    match (args.sort_by, args.top_n) {
+        (Some(key), _) => sort_services(&mut filtered_services, key, args.reverse),
+        (None, Some(_)) => sort_services(&mut filtered_services, SortKey::Exposure, !args.reverse),
+        (None, None) => {}
+    }
+
     if let Some(top_n) = args.top_n {
-        filtered_services.sort_by(|a, b| {
-            b.exposure
-                .partial_cmp(&a.exposure)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
         filtered_services = filtered_services.into_iter().take(top_n as usize).collect();
     }
 
-    // Output in JSON format if --json is set, for future parsing
-    // in CI/CD environments.
-    if args.json {
-        let result = AnalysisResult {
-            average_exposure: exposure_avg,
-            average_happiness: happiness_avg,
-            top_services: filtered_services,
-        };
-        let json_output =
-            serde_json::to_string_pretty(&result).expect("failed to serialize to json");
-        println!("{}", json_output);
-    } else {
+    if args.list {
+        for service in &filtered_services {
+            println!("{}", service.unit);
+        }
+        return;
+    }
+
+    let result = AnalysisResult {
+        average_exposure: exposure_avg,
+        average_happiness: happiness_avg,
+        top_services: filtered_services.clone(),
+    };
+
+    // Snapshots/comparisons always operate on the full unfiltered unit set,
+    // not whatever subset --predicate/--filter/--top-n happened to display,
+    // so a regression outside that subset isn't silently missed.
+    let full_result = AnalysisResult {
+        average_exposure: exposure_avg,
+        average_happiness: happiness_avg,
+        top_services: services.clone(),
+    };
+
+    // Under --json, the base result isn't printed yet: --compare may have a
+    // diff to fold in, and stdout must stay a single parseable document
+    // rather than two concatenated ones, so printing is deferred until the
+    // diff (if any) is in hand.
+    if !args.json {
         println!(
-            "{}\n\n{} {:.2} | {} {:.2}",
+            "{}\n\nAverage Exposure: {:.2} | Average Happiness: {:.2}",
             "# Systemd Security Analysis".bold().cyan(),
-            "Average Exposure:",
             exposure_avg,
-            "Average Happiness:",
             happiness_avg
         );
 
@@ -224,10 +579,10 @@ fn main() {
             "## Top".bold().cyan(),
             filtered_services.len(),
             "services for predicate:".bold().cyan(),
-            predicate.map_or("N/A".normal(), |pred| colorize_predicate(pred))
+            predicate.map_or("N/A".normal(), colorize_predicate)
         );
 
-        for service in filtered_services {
+        for service in &filtered_services {
             println!(
                 "{} {} {} ({} {:.2})",
                 "•".green(),
@@ -238,4 +593,188 @@ fn main() {
             );
         }
     }
+
+    // Compare against the prior baseline before overwriting it: if
+    // --snapshot and --compare point at the same file, this is the only
+    // order that lets the comparison see the outgoing baseline instead of
+    // diffing the new run against itself. This only decides whether to
+    // report a regression at the very end — it must not short-circuit the
+    // snapshot write, --deep, or --policy below, all of which still need to
+    // run regardless of whether this run regressed.
+    let compare_diff = args
+        .compare
+        .as_ref()
+        .map(|path| run_compare_mode(path, &full_result, args.json));
+    let regressed = compare_diff.as_ref().is_some_and(|diff| diff.any_regression);
+
+    if let Some(path) = &args.snapshot {
+        if let Err(err) = snapshot::save(&full_result, path) {
+            eprintln!(
+                "{} could not write snapshot to '{}': {}",
+                "error:".red().bold(),
+                path,
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let deep_output = run_deep_mode(&filtered_services, &args);
+    let policy_outcome = enforce_policy(&services, &args);
+
+    // The base result and, if present, the snapshot diff, deep diagnostics,
+    // and policy failures are folded into one JSON object and printed
+    // exactly once, so `--json` combined with any number of
+    // `--compare`/`--deep`/`--policy` emits a single document instead of
+    // several concatenated ones.
+    if args.json {
+        let mut payload = serde_json::to_value(&result).expect("failed to serialize to json");
+        let object = payload.as_object_mut().expect("AnalysisResult is an object");
+
+        if let Some(diff) = &compare_diff {
+            object.insert(
+                "snapshot_diff".to_string(),
+                serde_json::to_value(diff).expect("failed to serialize diff to json"),
+            );
+        }
+
+        if let Some(deep) = &deep_output {
+            if !(deep.diagnostics.is_empty() && deep.warnings.is_empty()) {
+                object.insert(
+                    "deep_diagnostics".to_string(),
+                    serde_json::to_value(&deep.diagnostics)
+                        .expect("failed to serialize deep diagnostics to json"),
+                );
+                object.insert(
+                    "deep_warnings".to_string(),
+                    serde_json::to_value(&deep.warnings)
+                        .expect("failed to serialize deep warnings to json"),
+                );
+            }
+        }
+
+        if let Some(policy) = &policy_outcome {
+            if !policy.failures.is_empty() {
+                object.insert(
+                    "policy_failures".to_string(),
+                    serde_json::to_value(&policy.failures)
+                        .expect("failed to serialize policy failures to json"),
+                );
+                object.insert(
+                    "policy_max_failures".to_string(),
+                    serde_json::to_value(args.policy_max_failures)
+                        .expect("failed to serialize policy_max_failures to json"),
+                );
+            }
+        }
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&payload).expect("failed to serialize payload to json")
+        );
+    }
+
+    // Exit decisions are made last, after the single JSON document (or the
+    // human-readable output above) has been printed. Policy takes priority
+    // over a snapshot regression, matching the order these checks used to
+    // run in before the exits were deferred.
+    if let Some(policy) = &policy_outcome {
+        if policy.exceeded {
+            eprintln!(
+                "{} {} unit(s) violated the policy (max allowed: {})",
+                "error:".red().bold(),
+                policy.failures.len(),
+                args.policy_max_failures
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if regressed {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(unit: &str, exposure: f64, happy: &str) -> Service {
+        Service {
+            unit: unit.to_string(),
+            exposure,
+            predicate: "MEDIUM".to_string(),
+            happy: happy.to_string(),
+        }
+    }
+
+    #[test]
+    fn sort_by_exposure_ascending() {
+        let mut services = vec![
+            service("c.service", 8.0, "😀"),
+            service("a.service", 1.0, "😀"),
+            service("b.service", 4.0, "😀"),
+        ];
+
+        sort_services(&mut services, SortKey::Exposure, false);
+
+        let units: Vec<&str> = services.iter().map(|s| s.unit.as_str()).collect();
+        assert_eq!(units, ["a.service", "b.service", "c.service"]);
+    }
+
+    #[test]
+    fn sort_by_exposure_reverse() {
+        let mut services = vec![
+            service("a.service", 1.0, "😀"),
+            service("b.service", 4.0, "😀"),
+        ];
+
+        sort_services(&mut services, SortKey::Exposure, true);
+
+        let units: Vec<&str> = services.iter().map(|s| s.unit.as_str()).collect();
+        assert_eq!(units, ["b.service", "a.service"]);
+    }
+
+    #[test]
+    fn sort_by_happy_ranks_by_happiness_not_codepoint() {
+        // 😨 (saddest) sorts after 😀 (happiest) by codepoint, but must come
+        // first by happiness rank: this is the exact bug 1a8847d fixed.
+        let mut services = vec![
+            service("happiest.service", 0.0, "😀"),
+            service("saddest.service", 0.0, "😨"),
+        ];
+
+        sort_services(&mut services, SortKey::Happy, false);
+
+        let units: Vec<&str> = services.iter().map(|s| s.unit.as_str()).collect();
+        assert_eq!(units, ["saddest.service", "happiest.service"]);
+    }
+
+    #[test]
+    fn sort_by_unit_is_alphabetical() {
+        let mut services = vec![
+            service("c.service", 0.0, "😀"),
+            service("a.service", 0.0, "😀"),
+            service("b.service", 0.0, "😀"),
+        ];
+
+        sort_services(&mut services, SortKey::Unit, false);
+
+        let units: Vec<&str> = services.iter().map(|s| s.unit.as_str()).collect();
+        assert_eq!(units, ["a.service", "b.service", "c.service"]);
+    }
+
+    #[test]
+    fn filter_substring_matches_anywhere_in_the_name() {
+        assert!(unit_matches("nginx.service", "nginx", false));
+        assert!(unit_matches("my-nginx.service", "nginx", false));
+        assert!(!unit_matches("apache.service", "nginx", false));
+    }
+
+    #[test]
+    fn filter_exact_requires_whole_name_match() {
+        assert!(unit_matches("nginx.service", "nginx.service", true));
+        assert!(!unit_matches("my-nginx.service", "nginx.service", true));
+        assert!(!unit_matches("nginx.service", "nginx", true));
+    }
 }
@@ -0,0 +1,435 @@
+//! Self-contained boolean expression engine used to evaluate `--policy`
+//! expressions against a [`Service`](crate::Service), e.g.
+//! `predicate != "UNSAFE" && exposure < 7.0`.
+
+use crate::Service;
+use std::fmt;
+
+/// A single token produced by [`tokenize`].
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(f64),
+    Str(String),
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+/// Comparison operators supported by `Expr::Compare`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    Eq,
+    Ne,
+}
+
+/// Parsed representation of a `--policy` expression.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Field(String),
+    NumLit(f64),
+    StrLit(String),
+}
+
+/// A typed value produced while evaluating an [`Expr`] against a [`Service`].
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+}
+
+/// Anything that can go wrong while tokenizing, parsing, or evaluating a
+/// policy expression. Surfaced to the user as an error message, never a
+/// panic.
+#[derive(Debug)]
+pub enum PolicyError {
+    UnexpectedChar(char),
+    UnterminatedString,
+    UnexpectedToken(String),
+    UnexpectedEof,
+    UnknownField(String),
+    TypeMismatch(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::UnexpectedChar(c) => {
+                write!(f, "unexpected character '{c}' in policy expression")
+            }
+            PolicyError::UnterminatedString => {
+                write!(f, "unterminated string literal in policy expression")
+            }
+            PolicyError::UnexpectedToken(t) => {
+                write!(f, "unexpected token '{t}' in policy expression")
+            }
+            PolicyError::UnexpectedEof => write!(f, "unexpected end of policy expression"),
+            PolicyError::UnknownField(field) => {
+                write!(
+                    f,
+                    "unknown field '{field}' (expected one of: unit, exposure, predicate, happy)"
+                )
+            }
+            PolicyError::TypeMismatch(msg) => {
+                write!(f, "type mismatch in policy expression: {msg}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, PolicyError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' | '\n' | '\r' => i += 1,
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '>' => {
+                if chars.get(i + 1) == Some(&'=') {
+                    tokens.push(Token::Ge);
+                    i += 2;
+                } else {
+                    tokens.push(Token::Gt);
+                    i += 1;
+                }
+            }
+            '<' => {
+                if chars.get(i + 1) == Some(&'=') {
+                    tokens.push(Token::Le);
+                    i += 2;
+                } else {
+                    tokens.push(Token::Lt);
+                    i += 1;
+                }
+            }
+            '=' => {
+                if chars.get(i + 1) == Some(&'=') {
+                    tokens.push(Token::Eq);
+                    i += 2;
+                } else {
+                    return Err(PolicyError::UnexpectedChar('='));
+                }
+            }
+            '!' => {
+                if chars.get(i + 1) == Some(&'=') {
+                    tokens.push(Token::Ne);
+                    i += 2;
+                } else {
+                    tokens.push(Token::Not);
+                    i += 1;
+                }
+            }
+            '&' if chars.get(i + 1) == Some(&'&') => {
+                tokens.push(Token::And);
+                i += 2;
+            }
+            '|' if chars.get(i + 1) == Some(&'|') => {
+                tokens.push(Token::Or);
+                i += 2;
+            }
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('"') => {
+                            i += 1;
+                            break;
+                        }
+                        Some(&ch) => {
+                            s.push(ch);
+                            i += 1;
+                        }
+                        None => return Err(PolicyError::UnterminatedString),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let lit: String = chars[start..i].iter().collect();
+                let num = lit
+                    .parse::<f64>()
+                    .map_err(|_| PolicyError::UnexpectedToken(lit.clone()))?;
+                tokens.push(Token::Num(num));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let ident: String = chars[start..i].iter().collect();
+                tokens.push(Token::Ident(ident));
+            }
+            other => return Err(PolicyError::UnexpectedChar(other)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive-descent parser over the token stream, lowest to highest
+/// precedence: `||`, `&&`, `!`, comparisons, then primaries.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, PolicyError> {
+        let mut lhs = self.parse_and()?;
+        while matches!(self.peek(), Some(Token::Or)) {
+            self.next();
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, PolicyError> {
+        let mut lhs = self.parse_not()?;
+        while matches!(self.peek(), Some(Token::And)) {
+            self.next();
+            let rhs = self.parse_not()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, PolicyError> {
+        if matches!(self.peek(), Some(Token::Not)) {
+            self.next();
+            let inner = self.parse_not()?;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        self.parse_compare()
+    }
+
+    fn parse_compare(&mut self) -> Result<Expr, PolicyError> {
+        let lhs = self.parse_primary()?;
+        let op = match self.peek() {
+            Some(Token::Gt) => Some(CompareOp::Gt),
+            Some(Token::Lt) => Some(CompareOp::Lt),
+            Some(Token::Ge) => Some(CompareOp::Ge),
+            Some(Token::Le) => Some(CompareOp::Le),
+            Some(Token::Eq) => Some(CompareOp::Eq),
+            Some(Token::Ne) => Some(CompareOp::Ne),
+            _ => None,
+        };
+
+        if let Some(op) = op {
+            self.next();
+            let rhs = self.parse_primary()?;
+            Ok(Expr::Compare(Box::new(lhs), op, Box::new(rhs)))
+        } else {
+            Ok(lhs)
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, PolicyError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Expr::Field(name)),
+            Some(Token::Num(n)) => Ok(Expr::NumLit(n)),
+            Some(Token::Str(s)) => Ok(Expr::StrLit(s)),
+            Some(Token::LParen) => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    Some(other) => Err(PolicyError::UnexpectedToken(format!("{other:?}"))),
+                    None => Err(PolicyError::UnexpectedEof),
+                }
+            }
+            Some(other) => Err(PolicyError::UnexpectedToken(format!("{other:?}"))),
+            None => Err(PolicyError::UnexpectedEof),
+        }
+    }
+}
+
+/// Parse a policy expression string into an [`Expr`] tree.
+pub fn parse(src: &str) -> Result<Expr, PolicyError> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(PolicyError::UnexpectedToken(format!(
+            "{:?}",
+            parser.tokens[parser.pos]
+        )));
+    }
+    Ok(expr)
+}
+
+/// Look up a field name against a [`Service`], typed per the field.
+fn field_value(service: &Service, field: &str) -> Result<Value, PolicyError> {
+    match field {
+        "exposure" => Ok(Value::Num(service.exposure)),
+        "predicate" => Ok(Value::Str(service.predicate.clone())),
+        "happy" => Ok(Value::Str(service.happy.clone())),
+        "unit" => Ok(Value::Str(service.unit.clone())),
+        other => Err(PolicyError::UnknownField(other.to_string())),
+    }
+}
+
+fn eval_value(expr: &Expr, service: &Service) -> Result<Value, PolicyError> {
+    match expr {
+        Expr::Field(name) => field_value(service, name),
+        Expr::NumLit(n) => Ok(Value::Num(*n)),
+        Expr::StrLit(s) => Ok(Value::Str(s.clone())),
+        Expr::Compare(lhs, op, rhs) => {
+            let result = eval_compare(lhs, *op, rhs, service)?;
+            Ok(Value::Bool(result))
+        }
+        Expr::And(lhs, rhs) => {
+            let l = eval_bool(lhs, service)?;
+            let r = eval_bool(rhs, service)?;
+            Ok(Value::Bool(l && r))
+        }
+        Expr::Or(lhs, rhs) => {
+            let l = eval_bool(lhs, service)?;
+            let r = eval_bool(rhs, service)?;
+            Ok(Value::Bool(l || r))
+        }
+        Expr::Not(inner) => Ok(Value::Bool(!eval_bool(inner, service)?)),
+    }
+}
+
+fn eval_compare(
+    lhs: &Expr,
+    op: CompareOp,
+    rhs: &Expr,
+    service: &Service,
+) -> Result<bool, PolicyError> {
+    let lval = eval_value(lhs, service)?;
+    let rval = eval_value(rhs, service)?;
+
+    match (lval, rval) {
+        (Value::Num(l), Value::Num(r)) => Ok(match op {
+            CompareOp::Gt => l > r,
+            CompareOp::Lt => l < r,
+            CompareOp::Ge => l >= r,
+            CompareOp::Le => l <= r,
+            CompareOp::Eq => l == r,
+            CompareOp::Ne => l != r,
+        }),
+        (Value::Str(l), Value::Str(r)) => match op {
+            CompareOp::Eq => Ok(l == r),
+            CompareOp::Ne => Ok(l != r),
+            _ => Err(PolicyError::TypeMismatch(format!(
+                "cannot apply ordering comparison to strings '{l}' and '{r}'"
+            ))),
+        },
+        (l, r) => Err(PolicyError::TypeMismatch(format!(
+            "cannot compare {l:?} with {r:?}"
+        ))),
+    }
+}
+
+fn eval_bool(expr: &Expr, service: &Service) -> Result<bool, PolicyError> {
+    match eval_value(expr, service)? {
+        Value::Bool(b) => Ok(b),
+        other => Err(PolicyError::TypeMismatch(format!(
+            "expected a boolean expression, got {other:?}"
+        ))),
+    }
+}
+
+/// Evaluate a parsed policy expression against a single [`Service`].
+pub fn evaluate(expr: &Expr, service: &Service) -> Result<bool, PolicyError> {
+    eval_bool(expr, service)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(unit: &str, exposure: f64, predicate: &str, happy: &str) -> Service {
+        Service {
+            unit: unit.to_string(),
+            exposure,
+            predicate: predicate.to_string(),
+            happy: happy.to_string(),
+        }
+    }
+
+    #[test]
+    fn evaluates_combined_expression() {
+        let expr = parse(r#"predicate != "UNSAFE" && exposure < 7.0"#).unwrap();
+        let ok = service("a.service", 3.0, "OK", "😀");
+        let unsafe_unit = service("b.service", 9.0, "UNSAFE", "😨");
+        assert!(evaluate(&expr, &ok).unwrap());
+        assert!(!evaluate(&expr, &unsafe_unit).unwrap());
+    }
+
+    #[test]
+    fn type_mismatch_is_an_error_not_a_panic() {
+        let expr = parse(r#"predicate > 5"#).unwrap();
+        let unit = service("a.service", 3.0, "OK", "😀");
+        match evaluate(&expr, &unit) {
+            Err(PolicyError::TypeMismatch(_)) => {}
+            other => panic!("expected a type mismatch error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_field_is_an_error_not_a_panic() {
+        let expr = parse("nonexistent == \"x\"").unwrap();
+        let unit = service("a.service", 3.0, "OK", "😀");
+        match evaluate(&expr, &unit) {
+            Err(PolicyError::UnknownField(field)) => assert_eq!(field, "nonexistent"),
+            other => panic!("expected an unknown field error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn empty_service_list_passes_vacuously() {
+        let expr = parse("exposure > 9000").unwrap();
+        let services: Vec<Service> = Vec::new();
+        let failures: Vec<&Service> = services
+            .iter()
+            .filter(|s| !evaluate(&expr, s).unwrap())
+            .collect();
+        assert!(failures.is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+//! Baseline snapshot persistence and regression diffing: `--snapshot <file>`
+//! saves the current `AnalysisResult` to disk, `--compare <file>` loads a
+//! prior one and reports what changed since.
+
+use crate::{AnalysisResult, Service};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// How a unit's predicate rank compares between a prior and current run.
+/// Higher is worse; used to decide whether a unit regressed.
+fn predicate_rank(predicate: &str) -> i32 {
+    match predicate {
+        "OK" => 0,
+        "MEDIUM" => 1,
+        "EXPOSED" => 2,
+        "UNSAFE" => 3,
+        _ => -1,
+    }
+}
+
+/// Per-unit comparison between a baseline snapshot and the current run.
+#[derive(Debug, Serialize)]
+pub struct UnitDiff {
+    pub unit: String,
+    pub status: UnitStatus,
+    pub exposure_before: Option<f64>,
+    pub exposure_after: Option<f64>,
+    pub exposure_delta: Option<f64>,
+    pub predicate_before: Option<String>,
+    pub predicate_after: Option<String>,
+    pub regressed: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub enum UnitStatus {
+    New,
+    Removed,
+    Changed,
+    Unchanged,
+}
+
+/// The full diff between two `AnalysisResult`s, machine-readable under `--json`.
+#[derive(Debug, Serialize)]
+pub struct SnapshotDiff {
+    pub units: Vec<UnitDiff>,
+    pub average_exposure_delta: f64,
+    pub average_happiness_delta: f64,
+    pub any_regression: bool,
+}
+
+/// Write `result` to `path` as pretty-printed JSON.
+pub fn save(result: &AnalysisResult, path: &str) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(result)?;
+    fs::write(path, json)
+}
+
+/// Load a previously-saved `AnalysisResult` from `path`.
+pub fn load(path: &str) -> Result<AnalysisResult, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("could not read snapshot file '{path}': {err}"))?;
+    serde_json::from_str(&contents)
+        .map_err(|err| format!("could not parse snapshot file '{path}': {err}"))
+}
+
+/// Diff a prior snapshot against the current run's result, matching units
+/// by name and flagging any that regressed (predicate tier worsened, or
+/// exposure increased at the same tier, or a newly-appeared unit is not OK).
+pub fn diff(before: &AnalysisResult, after: &AnalysisResult) -> SnapshotDiff {
+    let before_by_unit: HashMap<&str, &Service> = before
+        .top_services
+        .iter()
+        .map(|s| (s.unit.as_str(), s))
+        .collect();
+    let after_by_unit: HashMap<&str, &Service> = after
+        .top_services
+        .iter()
+        .map(|s| (s.unit.as_str(), s))
+        .collect();
+
+    let mut units = Vec::new();
+    let mut any_regression = false;
+
+    for service in &before.top_services {
+        if !after_by_unit.contains_key(service.unit.as_str()) {
+            units.push(UnitDiff {
+                unit: service.unit.clone(),
+                status: UnitStatus::Removed,
+                exposure_before: Some(service.exposure),
+                exposure_after: None,
+                exposure_delta: None,
+                predicate_before: Some(service.predicate.clone()),
+                predicate_after: None,
+                regressed: false,
+            });
+        }
+    }
+
+    for service in &after.top_services {
+        match before_by_unit.get(service.unit.as_str()) {
+            None => {
+                let regressed = predicate_rank(&service.predicate) > predicate_rank("OK");
+                any_regression |= regressed;
+                units.push(UnitDiff {
+                    unit: service.unit.clone(),
+                    status: UnitStatus::New,
+                    exposure_before: None,
+                    exposure_after: Some(service.exposure),
+                    exposure_delta: None,
+                    predicate_before: None,
+                    predicate_after: Some(service.predicate.clone()),
+                    regressed,
+                });
+            }
+            Some(prior) => {
+                let exposure_delta = service.exposure - prior.exposure;
+                let rank_before = predicate_rank(&prior.predicate);
+                let rank_after = predicate_rank(&service.predicate);
+                let regressed =
+                    rank_after > rank_before || (rank_after == rank_before && exposure_delta > 0.0);
+                any_regression |= regressed;
+
+                let status = if prior.predicate == service.predicate && exposure_delta == 0.0 {
+                    UnitStatus::Unchanged
+                } else {
+                    UnitStatus::Changed
+                };
+
+                units.push(UnitDiff {
+                    unit: service.unit.clone(),
+                    status,
+                    exposure_before: Some(prior.exposure),
+                    exposure_after: Some(service.exposure),
+                    exposure_delta: Some(exposure_delta),
+                    predicate_before: Some(prior.predicate.clone()),
+                    predicate_after: Some(service.predicate.clone()),
+                    regressed,
+                });
+            }
+        }
+    }
+
+    SnapshotDiff {
+        units,
+        average_exposure_delta: after.average_exposure - before.average_exposure,
+        average_happiness_delta: after.average_happiness - before.average_happiness,
+        any_regression,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(unit: &str, exposure: f64, predicate: &str) -> Service {
+        Service {
+            unit: unit.to_string(),
+            exposure,
+            predicate: predicate.to_string(),
+            happy: "😀".to_string(),
+        }
+    }
+
+    fn result(services: Vec<Service>) -> AnalysisResult {
+        let exposure_avg =
+            services.iter().map(|s| s.exposure).sum::<f64>() / services.len().max(1) as f64;
+        AnalysisResult {
+            average_exposure: exposure_avg,
+            average_happiness: 5.0,
+            top_services: services,
+        }
+    }
+
+    #[test]
+    fn flags_worsened_predicate_as_regression() {
+        let before = result(vec![service("a.service", 1.0, "OK")]);
+        let after = result(vec![service("a.service", 6.0, "EXPOSED")]);
+
+        let diff = diff(&before, &after);
+        assert!(diff.any_regression);
+        assert_eq!(diff.units[0].status, UnitStatus::Changed);
+        assert!(diff.units[0].regressed);
+    }
+
+    #[test]
+    fn does_not_flag_improved_predicate_as_regression() {
+        let before = result(vec![service("a.service", 8.0, "UNSAFE")]);
+        let after = result(vec![service("a.service", 2.0, "OK")]);
+
+        let diff = diff(&before, &after);
+        assert!(!diff.any_regression);
+        assert!(!diff.units[0].regressed);
+    }
+
+    #[test]
+    fn detects_new_and_removed_units() {
+        let before = result(vec![service("removed.service", 1.0, "OK")]);
+        let after = result(vec![service("new.service", 8.0, "UNSAFE")]);
+
+        let diff = diff(&before, &after);
+        let removed = diff
+            .units
+            .iter()
+            .find(|u| u.unit == "removed.service")
+            .unwrap();
+        let new = diff.units.iter().find(|u| u.unit == "new.service").unwrap();
+
+        assert_eq!(removed.status, UnitStatus::Removed);
+        assert!(!removed.regressed);
+        assert_eq!(new.status, UnitStatus::New);
+        assert!(
+            new.regressed,
+            "a newly-appeared UNSAFE unit should count as a regression"
+        );
+    }
+
+    #[test]
+    fn unchanged_unit_is_not_a_regression() {
+        let before = result(vec![service("a.service", 3.0, "MEDIUM")]);
+        let after = result(vec![service("a.service", 3.0, "MEDIUM")]);
+
+        let diff = diff(&before, &after);
+        assert_eq!(diff.units[0].status, UnitStatus::Unchanged);
+        assert!(!diff.units[0].regressed);
+        assert!(!diff.any_regression);
+    }
+}